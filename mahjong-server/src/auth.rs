@@ -0,0 +1,83 @@
+//! Authentication for clients logging into an existing account.
+//!
+//! `GameState` owns an `AccountStore` mapping each account's current credential token to
+//! its `GameProfile`, consulted whenever a client's handshake includes credentials that
+//! don't resume a disconnected session.
+
+use mahjong::messages::Credentials;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Identifies a player profile independent of whatever credential token currently grants
+/// access to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProfileId(u64);
+
+/// A persisted player profile, as opposed to the ephemeral credential token used to log
+/// into it.
+#[derive(Debug, Clone)]
+pub struct GameProfile {
+    pub id: ProfileId,
+    pub name: String,
+}
+
+/// Reasons an existing-account login can fail.
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("No account exists for the provided credentials")]
+    UnknownAccount,
+
+    #[error("This credential token has been revoked and can no longer be used to log in")]
+    TokenRevoked,
+}
+
+/// Sent to the client in place of a `HandshakeResponse` when its credentials don't grant
+/// access to an account.
+#[derive(Debug, Serialize)]
+#[serde(tag = "reason")]
+pub enum HandshakeRejection {
+    UnknownAccount,
+    TokenRevoked,
+}
+
+impl From<&AuthError> for HandshakeRejection {
+    fn from(err: &AuthError) -> Self {
+        match err {
+            AuthError::UnknownAccount => Self::UnknownAccount,
+            AuthError::TokenRevoked => Self::TokenRevoked,
+        }
+    }
+}
+
+/// Maps credential tokens to the profiles they currently grant access to.
+#[derive(Default)]
+pub struct AccountStore {
+    profiles: HashMap<Credentials, GameProfile>,
+    revoked: HashSet<Credentials>,
+}
+
+impl AccountStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grants `credentials` access to `profile`, e.g. after creating a new account.
+    pub fn register(&mut self, credentials: Credentials, profile: GameProfile) {
+        self.profiles.insert(credentials, profile);
+    }
+
+    /// Revokes `credentials`, e.g. after it's been replaced by a newer token.
+    pub fn revoke(&mut self, credentials: Credentials) {
+        self.profiles.remove(&credentials);
+        self.revoked.insert(credentials);
+    }
+
+    /// Looks up the profile that `credentials` currently grants access to.
+    pub fn authenticate(&self, credentials: &Credentials) -> Result<&GameProfile, AuthError> {
+        if self.revoked.contains(credentials) {
+            return Err(AuthError::TokenRevoked);
+        }
+
+        self.profiles.get(credentials).ok_or(AuthError::UnknownAccount)
+    }
+}
@@ -0,0 +1,61 @@
+//! A scripted player used to fill seats that no human has claimed.
+
+use crate::match_controller::*;
+use mahjong::{messages::*, tile::Wind};
+use thespian::{Actor, Remote, StageBuilder};
+use tracing::*;
+
+/// A simple bot that occupies a seat so a match can be played without four humans.
+///
+/// Like `ClientController`, it's joined to a `MatchControllerProxy` and receives
+/// `MatchEvent`s, but instead of forwarding them to a socket it drives a fixed policy:
+/// discard the tile it just drew, and never call any melds.
+#[derive(Debug, Actor)]
+pub struct BotController {
+    wind: Wind,
+    controller: MatchControllerProxy,
+    remote: Remote<Self>,
+}
+
+impl BotController {
+    /// Spawns a bot and joins it to `controller` as `wind`.
+    pub async fn spawn(mut controller: MatchControllerProxy, wind: Wind) {
+        let (builder, remote) = StageBuilder::new();
+        let stage = builder.finish(BotController {
+            wind,
+            controller: controller.clone(),
+            remote,
+        });
+        let bot = stage.proxy();
+        tokio::spawn(stage.run());
+
+        controller
+            .join(bot, wind)
+            .expect("Match controller died before match ended")
+            .await
+            .expect("Failed to join the match as a bot");
+    }
+}
+
+#[thespian::actor]
+impl BotController {
+    /// Reacts to a match event by discarding the tile it just drew, if it was this bot's
+    /// turn.
+    pub async fn send_event(&mut self, event: MatchEvent) {
+        let MatchEvent::TileDrawn { wind, tile } = event else {
+            return;
+        };
+
+        if wind != self.wind {
+            return;
+        }
+
+        trace!(?wind, ?tile, "Bot discarding the tile it just drew");
+
+        let _ = self
+            .controller
+            .discard_tile(wind, tile)
+            .expect("Match controller died before match ended")
+            .await;
+    }
+}
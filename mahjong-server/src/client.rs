@@ -1,15 +1,35 @@
-use crate::{match_controller::*, GameState};
+use crate::{
+    auth::HandshakeRejection,
+    bot::BotController,
+    match_controller::*,
+    matchmaking::{InviteCodeResponse, JOINABLE_WINDS},
+    protocol::ServerMessage,
+    GameState,
+};
 use derive_more::Display;
 use futures::{
     prelude::*,
     stream::{SplitSink, SplitStream},
 };
 use mahjong::{anyhow::*, messages::*, tile::Wind};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
 use thespian::{Actor, Remote, StageBuilder};
+use tokio::time;
 use tracing::*;
 use warp::{filters::ws::Message as WsMessage, ws::WebSocket};
 
+/// How long we wait for the client to complete the initial handshake before giving up.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often we ping the client to check that it's still alive.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long we'll go without hearing from the client before we consider it dead.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(120);
+
 /// Actor managing an active session with a client.
 #[derive(Debug, Actor)]
 pub struct ClientController {
@@ -18,8 +38,17 @@ pub struct ClientController {
     /// The sender half of the socket connection with the client.
     sink: SplitSink<WebSocket, WsMessage>,
     game: <GameState as Actor>::Proxy,
+
+    /// The account this session is logged in as, used to record this session's state
+    /// (including the account data needed to resume it) in the reconnection router if
+    /// the connection drops.
+    account: Account,
+
     state: ClientState,
 
+    /// The last time we received a message (including a Pong) from the client.
+    last_seen: Instant,
+
     remote: Remote<Self>,
 }
 
@@ -47,13 +76,12 @@ impl ClientController {
 
         trace!("Sent the client the initial ping, awaiting the handshake request");
 
-        // Wait for the client to send the handshake.
-        //
-        // TODO: Include a timeout so that we don't wait forever, otherwise this is a vector
-        // for DOS attacks.
-        let request = stream
-            .next()
+        // Wait for the client to send the handshake, giving up if it takes too long. Without
+        // this a client that connects and then goes quiet would hang the handshake forever,
+        // which is a vector for DOS attacks.
+        let request = time::timeout(HANDSHAKE_TIMEOUT, stream.next())
             .await
+            .map_err(|_| anyhow!("Client timed out during initial handshake"))?
             .ok_or(anyhow!("Client disconnected during initial handshake"))?
             .context("Waiting for response to handshake ping")?;
 
@@ -76,11 +104,33 @@ impl ClientController {
             todo!("Handle incompatible client version");
         }
 
-        // Get account information from the server, creating a new account if the client
-        // did not provide credentials for an existing account.
-        let account = match request.credentials {
-            Some(..) => todo!("Support logging into an existing account"),
-            None => game.create_account()?.await,
+        // Get account information from the server. If the client provided credentials for
+        // an account with a live match waiting to be resumed, re-attach to that match
+        // instead of going through a fresh login. A brand new account is handed its
+        // credentials back so it can log in again later; a returning account already has
+        // them, so there's nothing to send back.
+        let (account, new_credentials, resume) = match request.credentials {
+            Some(credentials) => match game.resume_session(credentials.clone())?.await {
+                Some(resumed) => (resumed.account, None, Some((resumed.controller, resumed.wind))),
+                None => match game.authenticate(credentials)?.await {
+                    Ok(account) => (account, None, None),
+                    Err(err) => {
+                        warn!(%err, "Rejecting handshake due to invalid credentials");
+
+                        let rejection = HandshakeRejection::from(&err);
+                        let rejection = serde_json::to_string(&rejection)
+                            .expect("Failed to serialize `HandshakeRejection`");
+                        sink.send(WsMessage::text(rejection)).await?;
+
+                        bail!("Rejected handshake: {}", err);
+                    }
+                },
+            },
+            None => {
+                let account = game.create_account()?.await;
+                let new_credentials = Some(account.credentials.clone());
+                (account, new_credentials, None)
+            }
         };
 
         info!("Verified handshake request, completing client connection");
@@ -88,26 +138,48 @@ impl ClientController {
         // Create the response message and send it to the client.
         let response = HandshakeResponse {
             server_version,
-            new_credentials: Some(account.credentials),
-            account_data: account.data,
+            new_credentials,
+            account_data: account.data.clone(),
         };
         let response =
             serde_json::to_string(&response).expect("Failed to serialize `HandshakeResponse`");
         sink.send(WsMessage::text(response)).await?;
 
+        // If we're resuming an in-progress match, replay its current state so the client
+        // can pick back up where it left off.
+        let state = if let Some((controller, wind)) = &resume {
+            trace!(?wind, "Resuming a disconnected session");
+
+            let state = controller
+                .current_state(*wind)
+                .context("Match controller died while client was disconnected")?
+                .await;
+            let response = serde_json::to_string(&StartMatchResponse { state })
+                .expect("Failed to serialize `StartMatchResponse`");
+            sink.send(WsMessage::text(response)).await?;
+
+            ClientState::InMatch {
+                controller: controller.clone(),
+                wind: *wind,
+            }
+        } else {
+            ClientState::Idle
+        };
+
         // Create the actor for the client connection and spawn it.
         let (builder, remote) = StageBuilder::new();
         let stage = builder.finish(ClientController {
             id,
             sink,
             game,
-            state: ClientState::Idle,
+            account,
+            state,
+            last_seen: Instant::now(),
             remote,
         });
         let client = stage.proxy();
         tokio::spawn(stage.run());
-
-        // TODO: Track the active session in the central game state.
+        tokio::spawn(Self::heartbeat_loop(client.clone()));
 
         Ok((client, stream))
     }
@@ -119,6 +191,47 @@ impl ClientController {
             .await
             .context("Failed to send message to client")
     }
+
+    /// Serializes `message` and sends it to the client.
+    async fn send_message(&mut self, message: ServerMessage) -> Result<()> {
+        let text =
+            serde_json::to_string(&message).expect("Failed to serialize `ServerMessage`");
+        self.send_text(text).await
+    }
+
+    /// Joins `controller` as `wind`, sends the client the resulting `StartMatchResponse`,
+    /// and transitions the controller to `ClientState::InMatch`.
+    async fn enter_match(&mut self, mut controller: MatchControllerProxy, wind: Wind) -> Result<()> {
+        let state = controller
+            .join(self.remote.proxy(), wind)
+            .expect("Match controller died before match ended")
+            .await
+            .expect("Failed to join the match we were just assigned to");
+
+        trace!(?wind, "Joined match, transitioning controller to `InMatch`");
+
+        let response = serde_json::to_string(&StartMatchResponse { state })
+            .expect("Failed to serialize `StartMatchResponse`");
+        self.send_text(response).await?;
+
+        self.state = ClientState::InMatch { controller, wind };
+        Ok(())
+    }
+
+    /// Drives the heartbeat for a client session, pinging it at a fixed interval and
+    /// shutting the session down once it's gone quiet for longer than `CLIENT_TIMEOUT`.
+    async fn heartbeat_loop(mut client: <Self as Actor>::Proxy) {
+        let mut ticker = time::interval(HEARTBEAT_INTERVAL);
+
+        loop {
+            ticker.tick().await;
+
+            match client.send_heartbeat().expect("Client actor died").await {
+                true => {}
+                false => break,
+            }
+        }
+    }
 }
 
 #[thespian::actor]
@@ -127,6 +240,14 @@ impl ClientController {
         let span = trace_span!("handle_message", id = %self.id);
         let _span = span.enter();
 
+        // Any traffic from the client, including keepalive frames, counts as a sign of life.
+        self.last_seen = Instant::now();
+
+        if message.is_ping() || message.is_pong() {
+            trace!("Received keepalive frame from client");
+            return Ok(());
+        }
+
         let text = match message.to_str() {
             Ok(text) => text,
             Err(_) => bail!("Received non-text message: {:?}", message),
@@ -136,35 +257,112 @@ impl ClientController {
         info!(?request, "Handling incoming request");
 
         match request {
-            ClientRequest::StartMatch => {
-                // TODO: Do an error if the client is already in a match (or would otherwise not be
-                // able to start a match).
+            // None of the match-starting requests make sense if we're already seated at
+            // a match, so reject them all up front rather than repeating this check.
+            ClientRequest::StartMatch
+            | ClientRequest::StartMatchWithBots
+            | ClientRequest::CreateMatch
+            | ClientRequest::JoinMatch { .. }
+            | ClientRequest::QuickMatch
+                if matches!(self.state, ClientState::InMatch { .. }) =>
+            {
+                self.send_message(ServerMessage::AlreadyInMatch).await?;
+            }
 
+            ClientRequest::StartMatch => {
                 trace!("Asking the game controller to start a match...");
 
+                let controller = self.game.start_match().unwrap().await;
+                self.enter_match(controller, Wind::East).await?;
+            }
+
+            ClientRequest::StartMatchWithBots => {
+                trace!("Asking the game controller to start a solo match filled with bots...");
+
                 let mut controller = self.game.start_match().unwrap().await;
 
-                // Join the match as the East player.
-                let state = controller
-                    .join(self.remote.proxy(), Wind::East)
-                    .unwrap()
-                    .await
-                    .expect("Failed to join the match that we just started???");
+                for wind in JOINABLE_WINDS {
+                    BotController::spawn(controller.clone(), wind).await;
+                }
+
+                trace!("Bots seated, joining as the East player");
+                self.enter_match(controller, Wind::East).await?;
+            }
+
+            ClientRequest::CreateMatch => {
+                trace!("Asking the game controller to create an invitable match...");
+
+                let mut controller = self.game.start_match().unwrap().await;
+                let code = self
+                    .game
+                    .register_match(controller.clone())
+                    .expect("Game state actor died")
+                    .await;
 
-                trace!("Match started, joined as East player");
+                trace!(%code, "Match created, waiting for the other three players to join");
 
-                let response = serde_json::to_string(&StartMatchResponse { state })
-                    .expect("Failed to serialize `StartMatchResponse`");
+                let response = serde_json::to_string(&InviteCodeResponse { code })
+                    .expect("Failed to serialize `InviteCodeResponse`");
                 self.send_text(response).await?;
 
-                trace!("Sent initial state to client, transitioning controller to `InMatch`");
-                self.state = ClientState::InMatch { controller };
+                self.enter_match(controller, Wind::East).await?;
+            }
+
+            ClientRequest::JoinMatch { code } => {
+                trace!(%code, "Looking up match for invite code");
+
+                let (controller, wind) = match self
+                    .game
+                    .join_match(code)
+                    .expect("Game state actor died")
+                    .await
+                {
+                    Ok(joined) => joined,
+                    Err(err) => {
+                        self.send_message(ServerMessage::InviteCodeRejected {
+                            reason: err.to_string(),
+                        })
+                        .await?;
+                        return Ok(());
+                    }
+                };
+
+                self.enter_match(controller, wind).await?;
+            }
+
+            ClientRequest::QuickMatch => {
+                trace!("Looking for a waiting match to join, or creating a new one");
+
+                let (controller, wind) = match self
+                    .game
+                    .quick_match()
+                    .expect("Game state actor died")
+                    .await
+                {
+                    Ok(joined) => joined,
+                    Err(_) => {
+                        // No one else is waiting yet, so create a new match and take the
+                        // first seat ourselves.
+                        let controller = self.game.start_match().unwrap().await;
+                        self.game
+                            .register_match(controller.clone())
+                            .expect("Game state actor died")
+                            .await;
+                        (controller, Wind::East)
+                    }
+                };
+
+                self.enter_match(controller, wind).await?;
             }
 
             ClientRequest::DiscardTile(request) => {
                 let controller = match &mut self.state {
-                    ClientState::InMatch { controller } => controller,
-                    _ => bail!("Cannot discard a tile when not in a match"),
+                    ClientState::InMatch { controller, .. } => controller,
+                    _ => {
+                        self.send_message(ServerMessage::InvalidRequestForState)
+                            .await?;
+                        return Ok(());
+                    }
                 };
 
                 trace!("Forwarding discard request to match controller");
@@ -174,9 +372,11 @@ impl ClientController {
                     .expect("Match controller died before match ended")
                     .await;
 
-                match result {
-                    Ok(()) => {}
-                    Err(err) => todo!("Notify client that discard failed? {}", err),
+                if let Err(err) = result {
+                    self.send_message(ServerMessage::DiscardRejected {
+                        reason: err.to_string(),
+                    })
+                    .await?;
                 }
             }
         }
@@ -184,9 +384,46 @@ impl ClientController {
         Ok(())
     }
 
+    /// Pings the client and checks that it hasn't gone quiet for longer than
+    /// `CLIENT_TIMEOUT`. Returns `false` once the session should be torn down.
+    pub async fn send_heartbeat(&mut self) -> bool {
+        if self.last_seen.elapsed() > CLIENT_TIMEOUT {
+            warn!(id = %self.id, "Client has been idle too long, closing the connection");
+            self.close_session().await;
+            return false;
+        }
+
+        if let Err(err) = self.sink.send(WsMessage::ping(Vec::new())).await {
+            warn!(id = %self.id, %err, "Failed to send heartbeat ping, closing the connection");
+            self.close_session().await;
+            return false;
+        }
+
+        true
+    }
+
+    /// Tears down this session: tells the match controller (if any) that the player
+    /// dropped, closes the socket, resets state so a stray in-flight `MatchEvent` can't
+    /// be delivered to a closed sink, and stops this actor's stage.
+    async fn close_session(&mut self) {
+        if let ClientState::InMatch { controller, wind } = &mut self.state {
+            let _ = controller
+                .player_disconnected(self.id)
+                .expect("Match controller died before match ended")
+                .await;
+
+            self.game
+                .mark_disconnected(self.account.clone(), controller.clone(), *wind)
+                .expect("Game state actor died")
+                .await;
+        }
+
+        self.state = ClientState::Idle;
+        let _ = self.sink.send(WsMessage::close()).await;
+        self.remote.stop();
+    }
+
     /// Sends an event to the client independent of the request/response flow.
-    // TODO: Generalize this to work for all kinds of server-sent events once we have
-    // other events to send.
     pub async fn send_event(&mut self, event: MatchEvent) {
         trace!(id = %self.id, ?event, "Sending a server event to the client");
 
@@ -195,8 +432,19 @@ impl ClientController {
             "Received match event when client wasn't in a match"
         );
 
-        let message = serde_json::to_string(&event).expect("Failed to serialize match event");
-        self.send_text(message)
+        // Once a match ends there's nothing left to forward events for, so drop back to
+        // `Idle` and let the client start or join another match.
+        if let MatchEvent::MatchEnded { result } = event {
+            trace!(id = %self.id, "Match ended, returning client to `Idle`");
+            self.state = ClientState::Idle;
+
+            self.send_message(ServerMessage::MatchEnded { result })
+                .await
+                .expect("Disconnected from the client, probably");
+            return;
+        }
+
+        self.send_message(ServerMessage::MatchEvent(event))
             .await
             .expect("Disconnected from the client, probably");
     }
@@ -205,7 +453,10 @@ impl ClientController {
 #[derive(Debug, Clone)]
 enum ClientState {
     Idle,
-    InMatch { controller: MatchControllerProxy },
+    InMatch {
+        controller: MatchControllerProxy,
+        wind: Wind,
+    },
 }
 
 /// Identifier for a connected client session.
@@ -0,0 +1,209 @@
+//! Invite-code based matchmaking, allowing the three players who didn't create a match
+//! to find their way to the same `MatchController`.
+
+use crate::match_controller::*;
+use derive_more::Display;
+use mahjong::tile::Wind;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// How long an unused invite code stays valid before it's reclaimed.
+const INVITE_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
+/// Every wind other than East, who is always claimed by the player that created the match.
+pub(crate) const JOINABLE_WINDS: [Wind; 3] = [Wind::South, Wind::West, Wind::North];
+
+/// A short, human-typeable code used to invite other players to a specific match.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[display(fmt = "{}", _0)]
+pub struct InviteCode(u32);
+
+impl InviteCode {
+    /// Generates a new invite code. Codes are six digits, which is short enough to read
+    /// over voice chat but gives a large enough space that guessing a live code is
+    /// impractical.
+    fn generate() -> Self {
+        Self(rand::thread_rng().gen_range(100_000..1_000_000))
+    }
+}
+
+/// A match that's been registered for joining, with the winds claimed so far.
+struct PendingMatch<C> {
+    controller: C,
+    claimed: Vec<Wind>,
+    created_at: Instant,
+}
+
+/// Reasons a `JoinMatch` or `QuickMatch` request can fail.
+#[derive(Debug, thiserror::Error)]
+pub enum JoinMatchError {
+    #[error("No match was found for invite code {0}")]
+    UnknownCode(InviteCode),
+
+    #[error("That match is already full")]
+    MatchFull,
+
+    #[error("There's no match currently waiting for more players")]
+    NoWaitingMatch,
+}
+
+/// Sent back to the client that creates a match so it can share the code with the other
+/// three players.
+#[derive(Debug, Serialize)]
+pub struct InviteCodeResponse {
+    pub code: InviteCode,
+}
+
+/// Tracks matches that have been created for joining, keyed by invite code, until their
+/// code expires.
+///
+/// Owned by `GameState`, which allocates a fresh `MatchControllerProxy` on
+/// `CreateMatch`/`QuickMatch` and hands it off here to wait for the remaining seats to be
+/// filled.
+///
+/// Generic over the controller handle (`MatchControllerProxy` in production) so the wind
+/// allocation and expiry bookkeeping, which don't care what kind of handle they're
+/// shuffling around, can be unit tested without spinning up a real actor.
+pub struct MatchRegistry<C = MatchControllerProxy> {
+    pending: HashMap<InviteCode, PendingMatch<C>>,
+}
+
+impl<C> Default for MatchRegistry<C> {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<C: Clone> MatchRegistry<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a freshly created match, with `Wind::East` claimed for the player that
+    /// created it, and returns the invite code other players can use to join it.
+    pub fn create(&mut self, controller: C) -> InviteCode {
+        self.sweep_expired();
+
+        let code = loop {
+            let code = InviteCode::generate();
+            if !self.pending.contains_key(&code) {
+                break code;
+            }
+        };
+
+        self.pending.insert(
+            code,
+            PendingMatch {
+                controller,
+                claimed: vec![Wind::East],
+                created_at: Instant::now(),
+            },
+        );
+
+        code
+    }
+
+    /// Looks up the match for `code` and allocates it the next free wind.
+    ///
+    /// Once all four winds are claimed the entry stays in the table until its code
+    /// expires, so a later join with the same code is rejected as `MatchFull`.
+    pub fn join(&mut self, code: InviteCode) -> Result<(C, Wind), JoinMatchError> {
+        self.sweep_expired();
+
+        let pending = self
+            .pending
+            .get_mut(&code)
+            .ok_or(JoinMatchError::UnknownCode(code))?;
+
+        let wind = *JOINABLE_WINDS
+            .iter()
+            .find(|wind| !pending.claimed.contains(wind))
+            .ok_or(JoinMatchError::MatchFull)?;
+
+        pending.claimed.push(wind);
+        Ok((pending.controller.clone(), wind))
+    }
+
+    /// Joins whichever match with a free seat has been waiting longest for more players.
+    pub fn quick_match(&mut self) -> Result<(C, Wind), JoinMatchError> {
+        self.sweep_expired();
+
+        let code = *self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.claimed.len() < 4)
+            .min_by_key(|(_, pending)| pending.created_at)
+            .map(|(code, _)| code)
+            .ok_or(JoinMatchError::NoWaitingMatch)?;
+
+        self.join(code)
+    }
+
+    /// Drops any invite codes that have gone unused for longer than `INVITE_EXPIRY`.
+    fn sweep_expired(&mut self) {
+        self.pending
+            .retain(|_, pending| pending.created_at.elapsed() < INVITE_EXPIRY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stand-in for `MatchControllerProxy` that's cheap to construct in tests.
+    type TestController = u32;
+
+    #[test]
+    fn join_fills_every_seat_then_reports_the_match_full() {
+        let mut registry = MatchRegistry::<TestController>::new();
+        let code = registry.create(1);
+
+        let mut winds = vec![registry.join(code).unwrap().1];
+        winds.push(registry.join(code).unwrap().1);
+        winds.push(registry.join(code).unwrap().1);
+
+        assert_eq!(winds, JOINABLE_WINDS);
+
+        match registry.join(code) {
+            Err(JoinMatchError::MatchFull) => {}
+            other => panic!("expected `MatchFull`, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn join_rejects_an_unknown_code() {
+        let mut registry = MatchRegistry::<TestController>::new();
+        registry.create(1);
+
+        let bogus_code = InviteCode(0);
+        match registry.join(bogus_code) {
+            Err(JoinMatchError::UnknownCode(code)) => assert_eq!(code, bogus_code),
+            other => panic!("expected `UnknownCode`, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn join_treats_an_expired_code_as_unknown() {
+        let mut registry = MatchRegistry::<TestController>::new();
+        let code = registry.create(1);
+
+        // Back-date the pending match past `INVITE_EXPIRY` so the next `sweep_expired`
+        // (run at the top of `join`) reclaims it before the lookup happens.
+        registry
+            .pending
+            .get_mut(&code)
+            .unwrap()
+            .created_at -= INVITE_EXPIRY + Duration::from_secs(1);
+
+        match registry.join(code) {
+            Err(JoinMatchError::UnknownCode(unknown_code)) => assert_eq!(unknown_code, code),
+            other => panic!("expected `UnknownCode`, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! The server-to-client half of the WebSocket protocol.
+//!
+//! `MatchEvent`s and protocol-level responses to a request that couldn't be satisfied are
+//! sent as a `ServerMessage`, so that a misbehaving or out-of-sync client gets a
+//! serialized error back instead of the connection being dropped. The handshake messages
+//! and the responses to a successful match request (`StartMatchResponse`,
+//! `InviteCodeResponse`) are sent as their own types, not wrapped in a `ServerMessage`.
+
+use mahjong::messages::{MatchEvent, MatchResult};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub enum ServerMessage {
+    /// An update about the state of a match the client is seated at.
+    MatchEvent(MatchEvent),
+
+    /// A `DiscardTile` request couldn't be applied, e.g. because it wasn't the client's
+    /// turn or the tile wasn't in their hand.
+    DiscardRejected { reason: String },
+
+    /// The request doesn't make sense given the client's current state, e.g. discarding
+    /// a tile while not seated at a match.
+    InvalidRequestForState,
+
+    /// The client tried to start or join a match while already seated at one.
+    AlreadyInMatch,
+
+    /// A `JoinMatch` request couldn't be satisfied, e.g. because the invite code was
+    /// unknown, expired, or the match was already full.
+    InviteCodeRejected { reason: String },
+
+    /// The match the client was seated at has ended.
+    MatchEnded { result: MatchResult },
+}
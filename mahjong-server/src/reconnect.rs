@@ -0,0 +1,180 @@
+//! Session resumption for players who drop off mid-match.
+//!
+//! A dropped WebSocket shouldn't cost a player their seat. We track every account's
+//! session in a table keyed by its credentials so that a client reconnecting with the
+//! same credentials can be re-attached to the `MatchController` it was playing in,
+//! instead of being handed a brand new account.
+
+use crate::match_controller::*;
+use mahjong::{
+    messages::{Account, Credentials},
+    tile::Wind,
+};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// How long a `Disconnected` entry is kept around before the seat is given up for good.
+const RECONNECT_GRACE_PERIOD: Duration = Duration::from_secs(5 * 60);
+
+/// Where a tracked account's session currently stands.
+enum SessionState<A, C> {
+    /// The account exists but has never been seated at a match, so there's nothing to
+    /// resume if its connection drops.
+    Reserved,
+
+    /// The account was seated at `wind` in `controller` but its connection dropped at
+    /// `since`. It can be resumed until the grace period expires.
+    Disconnected {
+        account: A,
+        controller: C,
+        wind: Wind,
+        since: Instant,
+    },
+
+    /// The account currently has a live `ClientController` attached.
+    Connected,
+}
+
+/// A session that was successfully resumed, ready to be re-attached to a fresh
+/// `ClientController`.
+pub struct ResumedSession<A = Account, C = MatchControllerProxy> {
+    pub account: A,
+    pub controller: C,
+    pub wind: Wind,
+}
+
+/// Tracks account sessions across reconnects, keyed by the account's credentials.
+///
+/// Owned by `GameState`, which consults it during the handshake before falling back to
+/// treating the connection as a fresh login.
+///
+/// Generic over the credential key, account data, and controller handle (`Credentials`,
+/// `Account`, and `MatchControllerProxy` in production) so the state machine and its
+/// grace-period expiry, which don't care what any of those actually contain, can be unit
+/// tested without a socket or actor harness.
+pub struct SessionRouter<K = Credentials, A = Account, C = MatchControllerProxy> {
+    sessions: HashMap<K, SessionState<A, C>>,
+}
+
+impl<K, A, C> Default for SessionRouter<K, A, C> {
+    fn default() -> Self {
+        Self {
+            sessions: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, A: Clone, C: Clone> SessionRouter<K, A, C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a brand new account with no match to resume.
+    pub fn reserve(&mut self, key: K) {
+        self.sessions.insert(key, SessionState::Reserved);
+    }
+
+    /// Marks `key` as having an actively connected session.
+    pub fn mark_connected(&mut self, key: K) {
+        self.sessions.insert(key, SessionState::Connected);
+    }
+
+    /// Marks `key` as disconnected from an in-progress match, starting the reconnect
+    /// grace period.
+    pub fn mark_disconnected(&mut self, key: K, account: A, controller: C, wind: Wind) {
+        self.sessions.insert(
+            key,
+            SessionState::Disconnected {
+                account,
+                controller,
+                wind,
+                since: Instant::now(),
+            },
+        );
+    }
+
+    /// Attempts to resume a session for `key`, returning `Some` and marking the session
+    /// `Connected` if there's a live, unexpired match to rejoin.
+    pub fn resume(&mut self, key: &K) -> Option<ResumedSession<A, C>> {
+        self.sweep_expired();
+
+        match self.sessions.get(key)? {
+            SessionState::Disconnected {
+                account,
+                controller,
+                wind,
+                ..
+            } => {
+                let resumed = ResumedSession {
+                    account: account.clone(),
+                    controller: controller.clone(),
+                    wind: *wind,
+                };
+                self.sessions.insert(key.clone(), SessionState::Connected);
+                Some(resumed)
+            }
+            SessionState::Reserved | SessionState::Connected => None,
+        }
+    }
+
+    /// Drops any `Disconnected` entries whose grace period has elapsed, reclaiming the
+    /// abandoned seat.
+    fn sweep_expired(&mut self) {
+        self.sessions.retain(|_, state| match state {
+            SessionState::Disconnected { since, .. } => {
+                since.elapsed() < RECONNECT_GRACE_PERIOD
+            }
+            SessionState::Reserved | SessionState::Connected => true,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestRouter = SessionRouter<u32, u32, u32>;
+
+    #[test]
+    fn resume_succeeds_before_the_grace_period_elapses() {
+        let mut router = TestRouter::new();
+        router.mark_disconnected(1, 100, 200, Wind::South);
+
+        let resumed = router.resume(&1).expect("session should still be resumable");
+        assert_eq!(resumed.account, 100);
+        assert_eq!(resumed.controller, 200);
+        assert_eq!(resumed.wind, Wind::South);
+    }
+
+    #[test]
+    fn resume_fails_once_the_grace_period_has_elapsed() {
+        let mut router = TestRouter::new();
+        router.mark_disconnected(1, 100, 200, Wind::South);
+
+        // Back-date the disconnect so the next `sweep_expired` (run at the top of
+        // `resume`) reclaims the seat before the lookup happens.
+        match router.sessions.get_mut(&1).unwrap() {
+            SessionState::Disconnected { since, .. } => {
+                *since -= RECONNECT_GRACE_PERIOD + Duration::from_secs(1);
+            }
+            _ => unreachable!(),
+        }
+
+        assert!(router.resume(&1).is_none());
+    }
+
+    #[test]
+    fn resume_only_succeeds_once() {
+        let mut router = TestRouter::new();
+        router.mark_disconnected(1, 100, 200, Wind::South);
+
+        assert!(router.resume(&1).is_some());
+        assert!(
+            router.resume(&1).is_none(),
+            "a session that's already `Connected` shouldn't be resumable again"
+        );
+    }
+}